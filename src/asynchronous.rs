@@ -0,0 +1,415 @@
+use super::*;
+use crate::download::{
+    AsyncClearStatus as _, AsyncDownloadChunk as _, AsyncErase as _, AsyncSetAddress as _,
+};
+use crate::get_status::AsyncGetStatus as _;
+use crate::upload::{AsyncClearStatus as _, AsyncUploadChunk as _};
+use futures::io::{AsyncRead, AsyncReadExt};
+use std::convert::TryFrom;
+
+/// Async counterpart of [`DfuIo`], for hosts (e.g. embassy/embedded executors) that cannot block
+/// the current task while waiting on USB transfers or on the device's poll timeout.
+///
+/// The associated types and methods otherwise mirror [`DfuIo`] one for one; see its
+/// documentation for what each call means on the wire.
+#[allow(async_fn_in_trait)] // single-threaded embedded executors are the target; requiring `Send` futures would be a needless constraint
+pub trait AsyncDfuIo {
+    /// Value returned by a read operation, see [`DfuIo::Read`].
+    type Read;
+    /// Value returned by a write operation, see [`DfuIo::Write`].
+    type Write;
+    /// Value returned by a USB reset, see [`DfuIo::Reset`].
+    type Reset;
+    /// Error type, see [`DfuIo::Error`].
+    type Error: From<Error>;
+
+    /// See [`DfuIo::functional_descriptor`].
+    fn functional_descriptor(&self) -> &memory_layout::FunctionalDescriptor;
+
+    /// See [`DfuIo::vendor_id`].
+    fn vendor_id(&self) -> u16;
+    /// See [`DfuIo::product_id`].
+    fn product_id(&self) -> u16;
+    /// See [`DfuIo::device_release`].
+    fn device_release(&self) -> u16;
+
+    /// See [`DfuIo::usb_detach`].
+    async fn usb_detach(&self) -> Result<Self::Write, Self::Error>;
+    /// See [`DfuIo::usb_reset`].
+    async fn usb_reset(&self) -> Result<Self::Reset, Self::Error>;
+    /// See [`DfuIo::usb_clear_status`].
+    async fn usb_clear_status(&self) -> Result<(), Self::Error>;
+    /// See [`DfuIo::usb_get_status`].
+    async fn usb_get_status(&self, buffer: &mut [u8]) -> Result<Self::Read, Self::Error>;
+
+    /// See [`DfuIo::usb_erase`].
+    async fn usb_erase(&self, address: u32, len: u32) -> Result<(), Self::Error>;
+    /// See [`DfuIo::usb_set_address`].
+    async fn usb_set_address(&self, address: u32) -> Result<(), Self::Error>;
+    /// See [`DfuIo::usb_download`].
+    async fn usb_download(&self, block_num: u16, bytes: &[u8]) -> Result<Self::Write, Self::Error>;
+    /// Issue a `DFU_UPLOAD` request and write the response into `buffer`.
+    async fn usb_upload(&self, block_num: u16, buffer: &mut [u8])
+        -> Result<Self::Read, Self::Error>;
+}
+
+/// An injected timer, used by [`DfuAsync`] to wait out a device's poll timeout without blocking
+/// the executor.
+#[allow(async_fn_in_trait)] // single-threaded embedded executors are the target; requiring `Send` futures would be a needless constraint
+pub trait AsyncTimer {
+    /// Wait for `milliseconds` before returning.
+    async fn delay(&mut self, milliseconds: u64);
+}
+
+/// Async, executor-agnostic mirror of [`crate::sync::DfuSync`].
+///
+/// It drives the exact same [`download::Step`]/[`get_status::Step`]/[`upload::Step`] state
+/// machines as `DfuSync`; only the transport (`AsyncDfuIo` instead of `DfuIo`) and the
+/// poll-timeout wait (an injected [`AsyncTimer`] instead of `std::thread::sleep`) differ.
+pub struct DfuAsync<IO, T, E>
+where
+    IO: AsyncDfuIo<Read = usize, Write = usize, Reset = (), Error = E>,
+    T: AsyncTimer,
+    E: From<std::io::Error> + From<Error> + std::error::Error + Send + Sync + 'static,
+{
+    dfu: DfuSansIo<IO>,
+    timer: T,
+    buffer: Vec<u8>,
+    progress: Option<Box<dyn FnMut(ProgressEvent)>>,
+    retries: RetryConfig,
+}
+
+impl<IO, T, E> DfuAsync<IO, T, E>
+where
+    IO: AsyncDfuIo<Read = usize, Write = usize, Reset = (), Error = E>,
+    T: AsyncTimer,
+    E: From<std::io::Error> + From<Error> + std::error::Error + Send + Sync + 'static,
+{
+    /// Create a new instance, using `timer` to wait out the device's poll timeouts.
+    pub fn new(io: IO, timer: T) -> Self {
+        let transfer_size = io.functional_descriptor().transfer_size as usize;
+
+        Self {
+            dfu: DfuSansIo::new(io),
+            timer,
+            buffer: vec![0x00; transfer_size],
+            progress: None,
+            retries: RetryConfig::default(),
+        }
+    }
+
+    /// Use this closure to show progress.
+    pub fn with_progress(&mut self, progress: impl FnMut(ProgressEvent) + 'static) -> &mut Self {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    /// Retry a recoverable transfer error or device-reported `dfuERROR` by resending the current
+    /// block, up to `max_attempts` times, before giving up with [`Error::RetriesExceeded`].
+    pub fn with_retries(&mut self, max_attempts: usize) -> &mut Self {
+        self.retries = RetryConfig { max_attempts };
+        self
+    }
+
+    /// Consume the object and return its [`AsyncDfuIo`].
+    pub fn into_inner(self) -> IO {
+        self.dfu.into_inner()
+    }
+}
+
+impl<IO, T, E> DfuAsync<IO, T, E>
+where
+    IO: AsyncDfuIo<Read = usize, Write = usize, Reset = (), Error = E>,
+    T: AsyncTimer,
+    E: From<std::io::Error> + From<Error> + std::error::Error + Send + Sync + 'static,
+{
+    /// Upload (read back) data from the device into `writer`.
+    ///
+    /// `length` is the maximum number of bytes to read; the upload may finish earlier if the
+    /// device ends it with a short or empty transfer.
+    pub async fn upload<W: futures::io::AsyncWrite + Unpin>(
+        &mut self,
+        mut writer: W,
+        length: u32,
+    ) -> Result<(), IO::Error> {
+        use futures::io::AsyncWriteExt;
+
+        // Polls status until the device is ready (`Ok`) or reports a `dfuERROR` (`Err`, with the
+        // resumed state handed back so the caller can clear it and resend). Status responses go
+        // to their own buffer so they never overwrite the uploaded block held in `self.buffer`.
+        macro_rules! wait_status {
+            ($cmd:expr) => {{
+                let mut cmd = $cmd;
+                let mut status = [0u8; 6];
+                loop {
+                    let (chained, n) = cmd.get_status(&mut status).await?;
+                    match chained.chain(&status[..n])? {
+                        get_status::Step::Break(resume) => break Ok(resume),
+                        get_status::Step::Error(resume) => break Err(resume),
+                        get_status::Step::Wait(next_cmd, poll_timeout) => {
+                            self.timer.delay(poll_timeout).await;
+                            cmd = next_cmd;
+                        }
+                    }
+                }
+            }};
+        }
+
+        // A `dfuERROR` with nothing sensible to resend yet: clear it and give up.
+        macro_rules! no_retry {
+            ($result:expr) => {
+                match $result {
+                    Ok(resume) => resume,
+                    Err(mut resume) => {
+                        resume.clear_status().await?;
+                        return Err(Error::StatusError.into());
+                    }
+                }
+            };
+        }
+
+        let mut upload_loop = no_retry!(wait_status!(self.dfu.upload(length)?));
+
+        let total = length as usize;
+        let mut remaining = total;
+        let mut transferred = 0usize;
+        loop {
+            upload_loop = match upload_loop.next() {
+                upload::Step::Break => break,
+                upload::Step::UploadChunk(cmd) => {
+                    let len = self.buffer.len().min(remaining);
+                    let mut cmd = cmd;
+                    let mut attempts = 0usize;
+                    let (resume, n) = loop {
+                        let (status_cmd, n) = match cmd.upload(&mut self.buffer[..len]).await {
+                            Ok(ok) => ok,
+                            Err((failed_cmd, err)) => {
+                                if !is_recoverable(&err) {
+                                    return Err(err);
+                                }
+                                attempts += 1;
+                                if attempts > self.retries.max_attempts {
+                                    return Err(Error::RetriesExceeded {
+                                        attempts,
+                                        source: Box::new(err),
+                                    }
+                                    .into());
+                                }
+                                cmd = failed_cmd.clear_status().await?;
+                                continue;
+                            }
+                        };
+                        match wait_status!(status_cmd) {
+                            Ok(resume) => break (resume, n),
+                            Err(mut resume) => {
+                                attempts += 1;
+                                if attempts > self.retries.max_attempts {
+                                    return Err(Error::RetriesExceeded {
+                                        attempts,
+                                        source: Box::new(Error::StatusError),
+                                    }
+                                    .into());
+                                }
+                                resume.clear_status().await?;
+                                resume.rewind_chunk();
+                                cmd = match resume.next() {
+                                    upload::Step::UploadChunk(cmd) => cmd,
+                                    _ => unreachable!("rewinding a chunk always yields a chunk"),
+                                };
+                            }
+                        }
+                    };
+                    writer.write_all(&self.buffer[..n]).await?;
+                    remaining = remaining.saturating_sub(n);
+                    transferred += n;
+                    if let Some(progress) = self.progress.as_mut() {
+                        progress(ProgressEvent::Transfer {
+                            bytes: transferred,
+                            total,
+                        });
+                    }
+                    resume
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Upload (read back) the whole content of the device into a [`Vec`].
+    pub async fn upload_to_vec(&mut self) -> Result<Vec<u8>, IO::Error> {
+        let mut data = Vec::new();
+        self.upload(&mut data, u32::MAX).await?;
+        Ok(data)
+    }
+
+    /// Download a firmware into the device.
+    pub async fn download<R: AsyncRead + Unpin>(
+        &mut self,
+        mut reader: R,
+        length: u32,
+    ) -> Result<(), IO::Error> {
+        // Status responses go to their own buffer so a block that is resent after a `dfuERROR`
+        // is still the data read into `self.buffer`.
+        macro_rules! wait_status {
+            ($cmd:expr) => {{
+                let mut cmd = $cmd;
+                let mut status = [0u8; 6];
+                loop {
+                    let (chained, n) = cmd.get_status(&mut status).await?;
+                    match chained.chain(&status[..n])? {
+                        get_status::Step::Break(resume) => break Ok(resume),
+                        get_status::Step::Error(resume) => break Err(resume),
+                        get_status::Step::Wait(next_cmd, poll_timeout) => {
+                            self.timer.delay(poll_timeout).await;
+                            cmd = next_cmd;
+                        }
+                    }
+                }
+            }};
+        }
+
+        macro_rules! no_retry {
+            ($result:expr) => {
+                match $result {
+                    Ok(resume) => resume,
+                    Err(mut resume) => {
+                        resume.clear_status().await?;
+                        return Err(Error::StatusError.into());
+                    }
+                }
+            };
+        }
+
+        let mut download_loop = no_retry!(wait_status!(self.dfu.download(length)?));
+
+        let total = length as usize;
+        let mut transferred = 0usize;
+        loop {
+            download_loop = match download_loop.next() {
+                download::Step::Break => break,
+                download::Step::Erase(cmd) => {
+                    if let Some(progress) = self.progress.as_mut() {
+                        progress(ProgressEvent::Erase {
+                            address: cmd.address(),
+                            len: cmd.len(),
+                        });
+                    }
+                    let (cmd, _) = cmd.erase().await?;
+                    no_retry!(wait_status!(cmd))
+                }
+                download::Step::SetAddress(cmd) => {
+                    if let Some(progress) = self.progress.as_mut() {
+                        progress(ProgressEvent::SetAddress);
+                    }
+                    let (cmd, _) = cmd.set_address().await?;
+                    no_retry!(wait_status!(cmd))
+                }
+                download::Step::DownloadChunk(cmd) => {
+                    let n = reader.read(&mut self.buffer).await?;
+                    let mut cmd = cmd;
+                    let mut attempts = 0usize;
+                    let (resume, n) = loop {
+                        let (status_cmd, n) = match cmd.download(&self.buffer[..n]).await {
+                            Ok(ok) => ok,
+                            Err((failed_cmd, err)) => {
+                                if !is_recoverable(&err) {
+                                    return Err(err);
+                                }
+                                attempts += 1;
+                                if attempts > self.retries.max_attempts {
+                                    return Err(Error::RetriesExceeded {
+                                        attempts,
+                                        source: Box::new(err),
+                                    }
+                                    .into());
+                                }
+                                cmd = failed_cmd.clear_status().await?;
+                                continue;
+                            }
+                        };
+                        match wait_status!(status_cmd) {
+                            Ok(resume) => break (resume, n),
+                            Err(mut resume) => {
+                                attempts += 1;
+                                if attempts > self.retries.max_attempts {
+                                    return Err(Error::RetriesExceeded {
+                                        attempts,
+                                        source: Box::new(Error::StatusError),
+                                    }
+                                    .into());
+                                }
+                                resume.clear_status().await?;
+                                resume.rewind_chunk(n as u32);
+                                cmd = match resume.next() {
+                                    download::Step::DownloadChunk(cmd) => cmd,
+                                    _ => unreachable!("rewinding a chunk always yields a chunk"),
+                                };
+                            }
+                        }
+                    };
+                    transferred += n;
+                    if let Some(progress) = self.progress.as_mut() {
+                        progress(ProgressEvent::Transfer {
+                            bytes: transferred,
+                            total,
+                        });
+                        if transferred >= total {
+                            progress(ProgressEvent::Manifest);
+                        }
+                    }
+                    resume
+                }
+                download::Step::UsbReset => {
+                    log::trace!("Device reset");
+                    if let Some(progress) = self.progress.as_mut() {
+                        progress(ProgressEvent::Reset);
+                    }
+                    self.dfu.io.usb_reset().await?;
+                    break;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Download a slice to on to the device.
+    pub async fn download_from_slice(&mut self, slice: &[u8]) -> Result<(), IO::Error> {
+        let length = u32::try_from(slice.len()).map_err(|_| Error::OutOfCapabilities)?;
+
+        self.download(futures::io::Cursor::new(slice), length)
+            .await
+    }
+
+    /// Verify `file`'s [`DfuSuffix`] against the device, then download everything preceding it.
+    ///
+    /// This is the opt-in counterpart of [`DfuAsync::download_from_slice`]: use it when `file` is
+    /// a full DFU file (firmware followed by its 16-byte suffix) rather than raw firmware bytes.
+    pub async fn download_from_slice_verified(&mut self, file: &[u8]) -> Result<(), IO::Error> {
+        let suffix = DfuSuffix::parse(file)?;
+        suffix.check_ids(
+            self.dfu.io.vendor_id(),
+            self.dfu.io.product_id(),
+            self.dfu.io.device_release(),
+        )?;
+
+        self.download_from_slice(&file[..file.len() - DfuSuffix::len()])
+            .await
+    }
+
+    /// Download a firmware into the device.
+    ///
+    /// The length is guessed from the reader by seeking to its end.
+    pub async fn download_all<R: AsyncRead + futures::io::AsyncSeek + Unpin>(
+        &mut self,
+        mut reader: R,
+    ) -> Result<(), IO::Error> {
+        use futures::io::AsyncSeekExt;
+
+        let length = u32::try_from(reader.seek(std::io::SeekFrom::End(0)).await?)
+            .map_err(|_| Error::MaximumTransferSizeExceeded)?;
+        reader.seek(std::io::SeekFrom::Start(0)).await?;
+        self.download(reader, length).await
+    }
+}
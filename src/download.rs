@@ -0,0 +1,297 @@
+use super::*;
+use crate::get_status::HasIo;
+
+/// Drives a download one step at a time; see [`DownloadLoop::next`].
+pub struct DownloadLoop<'io, IO> {
+    io: &'io mut IO,
+    length: u32,
+    offset: u32,
+    block_num: u16,
+    erased: bool,
+    address_set: bool,
+    transfer_size: u32,
+    manifestation_tolerant: bool,
+}
+
+impl<'io, IO> DownloadLoop<'io, IO> {
+    pub(crate) fn new(
+        io: &'io mut IO,
+        length: u32,
+        transfer_size: u32,
+        manifestation_tolerant: bool,
+    ) -> Self {
+        Self {
+            io,
+            length,
+            offset: 0,
+            block_num: 0,
+            erased: false,
+            address_set: false,
+            transfer_size,
+            manifestation_tolerant,
+        }
+    }
+
+    /// Undo [`DownloadChunkCmd::download`]'s bookkeeping so the same block is resent after
+    /// recovering from a `dfuERROR`.
+    pub(crate) fn rewind_chunk(&mut self, sent: u32) {
+        self.offset -= sent;
+        self.block_num = self.block_num.wrapping_sub(1);
+    }
+
+    /// Advance the state machine by one step.
+    ///
+    /// This is the part of the state machine shared verbatim between [`crate::sync::DfuSync`]
+    /// and [`crate::asynchronous::DfuAsync`]: only how the returned commands are driven (blocking
+    /// vs. `async`) differs between the two.
+    pub fn next(self) -> Step<'io, IO> {
+        if self.offset >= self.length {
+            if self.manifestation_tolerant {
+                Step::Break
+            } else {
+                Step::UsbReset
+            }
+        } else if !self.erased {
+            let len = self.transfer_size.min(self.length - self.offset);
+            let address = self.offset;
+            Step::Erase(EraseCmd {
+                address,
+                len,
+                resume: self,
+            })
+        } else if !self.address_set {
+            Step::SetAddress(SetAddressCmd { resume: self })
+        } else {
+            Step::DownloadChunk(DownloadChunkCmd { resume: self })
+        }
+    }
+}
+
+impl<'io, IO> HasIo for DownloadLoop<'io, IO> {
+    type Io = IO;
+
+    fn io_mut(&mut self) -> &mut IO {
+        self.io
+    }
+}
+
+/// Steps of the download state machine, returned by [`DownloadLoop::next`].
+pub enum Step<'io, IO> {
+    /// All data has been transferred and, if needed, manifestation has been waited out.
+    Break,
+    /// Erase the region about to be written.
+    Erase(EraseCmd<'io, IO>),
+    /// Tell the device the address the next chunk should be written at.
+    SetAddress(SetAddressCmd<'io, IO>),
+    /// Send the next chunk of data.
+    DownloadChunk(DownloadChunkCmd<'io, IO>),
+    /// The device needs an explicit USB reset to leave DFU mode (it is not manifestation
+    /// tolerant).
+    UsbReset,
+}
+
+impl<'io, IO: DfuIo> DownloadLoop<'io, IO> {
+    /// Issue `DFU_CLRSTATUS` to leave `dfuERROR`.
+    pub(crate) fn clear_status(&mut self) -> Result<(), IO::Error> {
+        self.io.usb_clear_status()
+    }
+}
+
+/// Async counterpart of [`DownloadLoop::clear_status`], kept as a separate trait rather than a
+/// second inherent impl — see [`crate::get_status::AsyncGetStatus`] for why.
+#[cfg(feature = "async")]
+#[allow(async_fn_in_trait)] // single-threaded embedded executors are the target; requiring `Send` futures would be a needless constraint
+pub trait AsyncClearStatus<IO> {
+    /// Error type of the underlying transport.
+    type Error;
+
+    /// Issue `DFU_CLRSTATUS` to leave `dfuERROR`.
+    async fn clear_status(&mut self) -> Result<(), Self::Error>;
+}
+
+#[cfg(feature = "async")]
+impl<'io, IO: crate::asynchronous::AsyncDfuIo> AsyncClearStatus<IO> for DownloadLoop<'io, IO> {
+    type Error = IO::Error;
+
+    async fn clear_status(&mut self) -> Result<(), IO::Error> {
+        self.io.usb_clear_status().await
+    }
+}
+
+/// A pending erase of the region about to be written.
+pub struct EraseCmd<'io, IO> {
+    address: u32,
+    len: u32,
+    resume: DownloadLoop<'io, IO>,
+}
+
+impl<'io, IO> EraseCmd<'io, IO> {
+    /// Start address of the region about to be erased.
+    pub fn address(&self) -> u32 {
+        self.address
+    }
+
+    /// Number of bytes about to be erased.
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+}
+
+impl<'io, IO: DfuIo> EraseCmd<'io, IO> {
+    /// Issue the erase request.
+    pub fn erase(mut self) -> Result<(get_status::Cmd<DownloadLoop<'io, IO>>, ()), IO::Error> {
+        self.resume.io.usb_erase(self.address, self.len)?;
+        self.resume.erased = true;
+        Ok((get_status::Cmd::immediate(self.resume), ()))
+    }
+}
+
+/// Async counterpart of [`EraseCmd::erase`], kept as a separate trait rather than a second
+/// inherent impl — see [`crate::get_status::AsyncGetStatus`] for why.
+#[cfg(feature = "async")]
+#[allow(async_fn_in_trait)] // single-threaded embedded executors are the target; requiring `Send` futures would be a needless constraint
+pub trait AsyncErase<'io, IO: 'io> {
+    /// Error type of the underlying transport.
+    type Error;
+
+    /// Issue the erase request.
+    async fn erase(self) -> Result<(get_status::Cmd<DownloadLoop<'io, IO>>, ()), Self::Error>;
+}
+
+#[cfg(feature = "async")]
+impl<'io, IO: crate::asynchronous::AsyncDfuIo> AsyncErase<'io, IO> for EraseCmd<'io, IO> {
+    type Error = IO::Error;
+
+    async fn erase(mut self) -> Result<(get_status::Cmd<DownloadLoop<'io, IO>>, ()), IO::Error> {
+        self.resume.io.usb_erase(self.address, self.len).await?;
+        self.resume.erased = true;
+        Ok((get_status::Cmd::immediate(self.resume), ()))
+    }
+}
+
+/// A pending `DFU_DNLOAD` address-setup request (`wBlockNum == 0`).
+pub struct SetAddressCmd<'io, IO> {
+    resume: DownloadLoop<'io, IO>,
+}
+
+impl<'io, IO: DfuIo> SetAddressCmd<'io, IO> {
+    /// Issue the address-setup request.
+    pub fn set_address(mut self) -> Result<(get_status::Cmd<DownloadLoop<'io, IO>>, ()), IO::Error> {
+        self.resume.io.usb_set_address(self.resume.offset)?;
+        self.resume.address_set = true;
+        Ok((get_status::Cmd::immediate(self.resume), ()))
+    }
+}
+
+/// Async counterpart of [`SetAddressCmd::set_address`], kept as a separate trait rather than a
+/// second inherent impl — see [`crate::get_status::AsyncGetStatus`] for why.
+#[cfg(feature = "async")]
+#[allow(async_fn_in_trait)] // single-threaded embedded executors are the target; requiring `Send` futures would be a needless constraint
+pub trait AsyncSetAddress<'io, IO: 'io> {
+    /// Error type of the underlying transport.
+    type Error;
+
+    /// Issue the address-setup request.
+    async fn set_address(self) -> Result<(get_status::Cmd<DownloadLoop<'io, IO>>, ()), Self::Error>;
+}
+
+#[cfg(feature = "async")]
+impl<'io, IO: crate::asynchronous::AsyncDfuIo> AsyncSetAddress<'io, IO> for SetAddressCmd<'io, IO> {
+    type Error = IO::Error;
+
+    async fn set_address(mut self) -> Result<(get_status::Cmd<DownloadLoop<'io, IO>>, ()), IO::Error> {
+        self.resume.io.usb_set_address(self.resume.offset).await?;
+        self.resume.address_set = true;
+        Ok((get_status::Cmd::immediate(self.resume), ()))
+    }
+}
+
+/// A pending `DFU_DNLOAD` data transfer.
+pub struct DownloadChunkCmd<'io, IO> {
+    resume: DownloadLoop<'io, IO>,
+}
+
+impl<'io, IO: DfuIo<Write = usize>> DownloadChunkCmd<'io, IO> {
+    /// Send `chunk` as the next block, returning the command to reach the next step together
+    /// with the number of bytes accepted by the device.
+    ///
+    /// On a transport error `self` is handed back unchanged (no byte of `chunk` was committed),
+    /// so the caller can resend the same block after recovering.
+    #[allow(clippy::type_complexity)]
+    pub fn download(
+        mut self,
+        chunk: &[u8],
+    ) -> Result<(get_status::Cmd<DownloadLoop<'io, IO>>, usize), (Self, IO::Error)> {
+        let block_num = self.resume.block_num;
+        match self.resume.io.usb_download(block_num, chunk) {
+            Ok(n) => {
+                self.resume.offset += n as u32;
+                self.resume.block_num = block_num.wrapping_add(1);
+                Ok((get_status::Cmd::immediate(self.resume), n))
+            }
+            Err(err) => Err((self, err)),
+        }
+    }
+
+    /// Issue `DFU_CLRSTATUS` to leave `dfuERROR`, handing `self` back unchanged so the block can
+    /// be resent.
+    pub fn clear_status(self) -> Result<Self, IO::Error> {
+        self.resume.io.usb_clear_status()?;
+        Ok(self)
+    }
+}
+
+/// Async counterpart of [`DownloadChunkCmd::download`], kept as a separate trait rather than a
+/// second inherent impl — see [`crate::get_status::AsyncGetStatus`] for why.
+#[cfg(feature = "async")]
+#[allow(async_fn_in_trait)] // single-threaded embedded executors are the target; requiring `Send` futures would be a needless constraint
+pub trait AsyncDownloadChunk<'io, IO: 'io> {
+    /// Error type of the underlying transport.
+    type Error;
+
+    /// Send `chunk` as the next block, returning the command to reach the next step together
+    /// with the number of bytes accepted by the device.
+    ///
+    /// On a transport error `self` is handed back unchanged (no byte of `chunk` was committed),
+    /// so the caller can resend the same block after recovering.
+    async fn download(
+        self,
+        chunk: &[u8],
+    ) -> Result<(get_status::Cmd<DownloadLoop<'io, IO>>, usize), (Self, Self::Error)>
+    where
+        Self: Sized;
+
+    /// Issue `DFU_CLRSTATUS` to leave `dfuERROR`, handing `self` back unchanged so the block can
+    /// be resent.
+    async fn clear_status(self) -> Result<Self, Self::Error>
+    where
+        Self: Sized;
+}
+
+#[cfg(feature = "async")]
+impl<'io, IO: crate::asynchronous::AsyncDfuIo<Write = usize>> AsyncDownloadChunk<'io, IO>
+    for DownloadChunkCmd<'io, IO>
+{
+    type Error = IO::Error;
+
+    async fn download(
+        mut self,
+        chunk: &[u8],
+    ) -> Result<(get_status::Cmd<DownloadLoop<'io, IO>>, usize), (Self, IO::Error)> {
+        let block_num = self.resume.block_num;
+        match self.resume.io.usb_download(block_num, chunk).await {
+            Ok(n) => {
+                self.resume.offset += n as u32;
+                self.resume.block_num = block_num.wrapping_add(1);
+                Ok((get_status::Cmd::immediate(self.resume), n))
+            }
+            Err(err) => Err((self, err)),
+        }
+    }
+
+    async fn clear_status(self) -> Result<Self, IO::Error> {
+        self.resume.io.usb_clear_status().await?;
+        Ok(self)
+    }
+}
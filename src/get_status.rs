@@ -0,0 +1,208 @@
+use super::*;
+
+/// `bState` values from a `DFU_GETSTATUS` response that matter to the host state machine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// `dfuDNBUSY`/`dfuMANIFEST`/... : the device is still processing the previous request.
+    Busy,
+    /// `dfuIDLE`/`dfuDNLOAD-IDLE`/`dfuUPLOAD-IDLE`/... : the device is ready for the next request.
+    Ready,
+    /// `dfuERROR`: the device aborted and needs a `DFU_CLRSTATUS` before it will accept anything
+    /// else.
+    Error,
+}
+
+/// Gives a state-machine type access to the single `IO` it was handed at the start of the
+/// operation, so [`Cmd`]/[`Chained`] don't need to carry a redundant borrow of their own.
+///
+/// Public only because it appears in the bounds of the public [`Cmd`] impls; not meant to be
+/// implemented outside this crate.
+pub trait HasIo {
+    /// The transport this state lives on.
+    type Io;
+
+    #[doc(hidden)]
+    fn io_mut(&mut self) -> &mut Self::Io;
+}
+
+/// A pending `DFU_GETSTATUS` request; call [`Cmd::get_status`] to issue it.
+///
+/// Generic over `R`, the state the caller resumes with once the device is confirmed ready -
+/// this is what lets the exact same type be handed back from every action in
+/// [`crate::download`] and [`crate::upload`] (they all resume their own loop type).
+pub struct Cmd<R> {
+    resume: R,
+}
+
+impl<R> Cmd<R> {
+    pub(crate) fn immediate(resume: R) -> Self {
+        Self { resume }
+    }
+}
+
+impl<R> Cmd<R>
+where
+    R: HasIo,
+    R::Io: DfuIo<Read = usize>,
+{
+    /// Issue `DFU_GETSTATUS`, reading the response into `buffer`.
+    pub fn get_status(
+        mut self,
+        buffer: &mut [u8],
+    ) -> Result<(Chained<R>, usize), <R::Io as DfuIo>::Error> {
+        let n = self.resume.io_mut().usb_get_status(buffer)?;
+        Ok((
+            Chained {
+                resume: self.resume,
+            },
+            n,
+        ))
+    }
+}
+
+/// Async counterpart of [`Cmd::get_status`], kept as a separate trait (rather than a second
+/// inherent impl) since an inherent method may only be defined once for a given type — the
+/// sync and async transports are distinguished by which of [`Cmd::get_status`]/this trait's
+/// `get_status` is in scope at the call site (see [`crate::sync`] vs [`crate::asynchronous`]).
+#[cfg(feature = "async")]
+#[allow(async_fn_in_trait)] // single-threaded embedded executors are the target; requiring `Send` futures would be a needless constraint
+pub trait AsyncGetStatus<R> {
+    /// Transport error, see [`crate::asynchronous::AsyncDfuIo::Error`].
+    type Error;
+
+    /// Issue `DFU_GETSTATUS`, reading the response into `buffer`.
+    async fn get_status(self, buffer: &mut [u8]) -> Result<(Chained<R>, usize), Self::Error>;
+}
+
+#[cfg(feature = "async")]
+impl<R> AsyncGetStatus<R> for Cmd<R>
+where
+    R: HasIo,
+    R::Io: crate::asynchronous::AsyncDfuIo<Read = usize>,
+{
+    type Error = <R::Io as crate::asynchronous::AsyncDfuIo>::Error;
+
+    async fn get_status(mut self, buffer: &mut [u8]) -> Result<(Chained<R>, usize), Self::Error> {
+        use crate::asynchronous::AsyncDfuIo as _;
+
+        let n = self.resume.io_mut().usb_get_status(buffer).await?;
+        Ok((
+            Chained {
+                resume: self.resume,
+            },
+            n,
+        ))
+    }
+}
+
+/// A `DFU_GETSTATUS` response has been read; call [`Chained::chain`] to interpret it.
+pub struct Chained<R> {
+    resume: R,
+}
+
+/// Outcome of interpreting a `DFU_GETSTATUS` response.
+pub enum Step<R> {
+    /// The device is ready; continue with the resumed state.
+    Break(R),
+    /// The device is still busy; wait `poll_timeout_ms`, then poll again.
+    Wait(Cmd<R>, u64),
+    /// The device reported a `dfuERROR` status. The resumed state is handed back unchanged so
+    /// the caller can issue `DFU_CLRSTATUS` and resend the current block.
+    Error(R),
+}
+
+impl<R> Chained<R> {
+    /// Interpret the `DFU_GETSTATUS` response in `status`.
+    pub fn chain(self, status: &[u8]) -> Result<Step<R>, Error> {
+        match parse_state(status)? {
+            State::Error => Ok(Step::Error(self.resume)),
+            State::Busy => {
+                let poll_timeout_ms = parse_poll_timeout(status);
+                Ok(Step::Wait(
+                    Cmd {
+                        resume: self.resume,
+                    },
+                    poll_timeout_ms,
+                ))
+            }
+            State::Ready => Ok(Step::Break(self.resume)),
+        }
+    }
+}
+
+fn parse_state(status: &[u8]) -> Result<State, Error> {
+    let b_state = *status.get(4).ok_or(Error::InvalidStatus)?;
+    Ok(match b_state {
+        0x0a => State::Error,
+        // dfuDNLOAD-SYNC(3), dfuDNBUSY(4), dfuMANIFEST-SYNC(6), dfuMANIFEST(7),
+        // dfuMANIFEST-WAIT-RESET(8): the device is still processing the previous request.
+        // dfuDNLOAD-IDLE(5) is ready for the next block.
+        0x03 | 0x04 | 0x06..=0x08 => State::Busy,
+        _ => State::Ready,
+    })
+}
+
+fn parse_poll_timeout(status: &[u8]) -> u64 {
+    let bytes = [
+        status.first().copied().unwrap_or(0),
+        status.get(1).copied().unwrap_or(0),
+        status.get(2).copied().unwrap_or(0),
+        0,
+    ];
+    u32::from_le_bytes(bytes) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(poll_timeout_ms: u32, b_state: u8) -> [u8; 6] {
+        let t = poll_timeout_ms.to_le_bytes();
+        [t[0], t[1], t[2], 0, b_state, 0]
+    }
+
+    fn chain(b_state: u8) -> Step<()> {
+        Chained { resume: () }.chain(&status(0, b_state)).unwrap()
+    }
+
+    #[test]
+    fn every_manifestation_and_sync_state_is_busy_not_ready() {
+        for b_state in [0x03, 0x04, 0x06, 0x07, 0x08] {
+            assert!(
+                matches!(chain(b_state), Step::Wait(..)),
+                "bState {b_state:#04x} should be Busy"
+            );
+        }
+    }
+
+    #[test]
+    fn idle_states_are_ready() {
+        for b_state in [0x00, 0x02, 0x05, 0x09] {
+            assert!(
+                matches!(chain(b_state), Step::Break(())),
+                "bState {b_state:#04x} should be Ready"
+            );
+        }
+    }
+
+    #[test]
+    fn dfu_error_state_hands_back_the_resume_state() {
+        assert!(matches!(chain(0x0a), Step::Error(())));
+    }
+
+    #[test]
+    fn wait_carries_the_poll_timeout() {
+        match (Chained { resume: () }).chain(&status(250, 0x04)).unwrap() {
+            Step::Wait(_, poll_timeout_ms) => assert_eq!(poll_timeout_ms, 250),
+            _ => panic!("expected Wait"),
+        }
+    }
+
+    #[test]
+    fn a_response_too_short_to_contain_bstate_is_invalid() {
+        assert!(matches!(
+            Chained { resume: () }.chain(&[0, 0, 0]),
+            Err(Error::InvalidStatus)
+        ));
+    }
+}
@@ -0,0 +1,275 @@
+//! Sans-io implementation of the USB DFU (Device Firmware Upgrade) protocol.
+//!
+//! [`DfuSansIo`] drives the DFU 1.1 state machine without performing any I/O itself; plug in a
+//! [`DfuIo`] implementation and drive it with [`sync::DfuSync`] (blocking), or an
+//! [`asynchronous::AsyncDfuIo`] implementation and drive it with [`asynchronous::DfuAsync`]
+//! (behind the `async` feature).
+
+#[cfg(feature = "async")]
+pub mod asynchronous;
+pub mod download;
+pub mod get_status;
+pub mod memory_layout;
+pub mod progress;
+pub mod suffix;
+#[cfg(feature = "std")]
+pub mod sync;
+pub mod upload;
+
+pub use memory_layout::FunctionalDescriptor;
+pub use progress::ProgressEvent;
+pub use suffix::DfuSuffix;
+
+/// Errors produced by this crate, independent of the transport's own [`DfuIo::Error`].
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The size of the data being transferred exceeds the device's capabilities.
+    OutOfCapabilities,
+    /// The firmware image is larger than can be represented in a `u32` length.
+    MaximumTransferSizeExceeded,
+    /// The device reported a `dfuERROR` status; a `DFU_CLRSTATUS` is needed before anything else
+    /// will be accepted.
+    StatusError,
+    /// The `DFU_GETSTATUS` response was too short to contain a status byte.
+    InvalidStatus,
+    /// The DFU file suffix is missing, too short, or has an invalid `bLength`/signature.
+    InvalidSuffix,
+    /// The DFU file suffix's `dwCRC` does not match the CRC32 computed over the file.
+    CrcMismatch {
+        /// The CRC32 stored in the file's suffix.
+        expected: u32,
+        /// The CRC32 actually computed over the file.
+        computed: u32,
+    },
+    /// A block failed `attempts` times in a row and the configured [`RetryConfig`] was
+    /// exhausted.
+    RetriesExceeded {
+        /// Number of attempts made, including the first.
+        attempts: usize,
+        /// The error from the last attempt.
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::OutOfCapabilities => write!(f, "data exceeds the device's capabilities"),
+            Error::MaximumTransferSizeExceeded => write!(f, "maximum transfer size exceeded"),
+            Error::StatusError => write!(f, "device reported a dfuERROR status"),
+            Error::InvalidStatus => write!(f, "device status response is invalid"),
+            Error::InvalidSuffix => write!(f, "DFU file suffix is missing or invalid"),
+            Error::CrcMismatch { expected, computed } => write!(
+                f,
+                "DFU file suffix CRC mismatch: expected {expected:#010x}, computed {computed:#010x}"
+            ),
+            Error::RetriesExceeded { attempts, source } => {
+                write!(f, "gave up after {attempts} attempts: {source}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Retry policy for recoverable transfer errors, see [`sync::DfuSync::with_retries`] (and its
+/// `DfuAsync` counterpart).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RetryConfig {
+    /// Maximum number of times a single block is resent after a recoverable failure, not
+    /// counting the first attempt. `0` (the default) disables retries entirely.
+    pub max_attempts: usize,
+}
+
+/// Whether `error` represents a failure worth retrying, as opposed to one that will never
+/// succeed no matter how many times the block is resent.
+///
+/// This crate has no insight into the specifics of a transport's own error type, so it only
+/// special-cases its own [`Error::StatusError`] (`dfuERROR` is, by definition, recoverable via
+/// `DFU_CLRSTATUS`) and [`Error::InvalidStatus`] (a malformed response, worth trying again);
+/// every other crate-level error is a configuration/capability mismatch that a retry can't fix.
+/// Transport errors outside this crate's `Error` are assumed to be transient (e.g. a USB NAK or
+/// stall) and therefore recoverable.
+pub(crate) fn is_recoverable(error: &(dyn std::error::Error + 'static)) -> bool {
+    !matches!(
+        error.downcast_ref::<Error>(),
+        Some(
+            Error::OutOfCapabilities
+                | Error::MaximumTransferSizeExceeded
+                | Error::InvalidSuffix
+                | Error::CrcMismatch { .. }
+                | Error::RetriesExceeded { .. }
+        )
+    )
+}
+
+/// Trait to implement lower level communication with a USB DFU device.
+///
+/// [`sync::DfuSync`] is generic over this, so the same state-machine driving logic works
+/// regardless of the underlying USB stack.
+pub trait DfuIo {
+    /// Value returned by a read operation (e.g. number of bytes read).
+    type Read;
+    /// Value returned by a write operation (e.g. number of bytes written).
+    type Write;
+    /// Value returned by [`DfuIo::usb_reset`].
+    type Reset;
+    /// Error type.
+    type Error: From<Error>;
+
+    /// Returns the functional descriptor of the device.
+    fn functional_descriptor(&self) -> &memory_layout::FunctionalDescriptor;
+
+    /// `idVendor` of the device, used by [`DfuSuffix::check_ids`].
+    fn vendor_id(&self) -> u16;
+    /// `idProduct` of the device, used by [`DfuSuffix::check_ids`].
+    fn product_id(&self) -> u16;
+    /// `bcdDevice` of the device, used by [`DfuSuffix::check_ids`].
+    fn device_release(&self) -> u16;
+
+    /// Issue a `DFU_DETACH` request.
+    fn usb_detach(&self) -> Result<Self::Write, Self::Error>;
+    /// Trigger a USB reset.
+    fn usb_reset(&self) -> Result<Self::Reset, Self::Error>;
+    /// Issue `DFU_CLRSTATUS`, to leave `dfuERROR` after a recoverable failure.
+    fn usb_clear_status(&self) -> Result<(), Self::Error>;
+    /// Issue `DFU_GETSTATUS`, writing the response into `buffer`.
+    fn usb_get_status(&self, buffer: &mut [u8]) -> Result<Self::Read, Self::Error>;
+
+    /// Erase `len` bytes starting at `address` (DfuSe extension; a no-op for plain DFU 1.1
+    /// devices).
+    fn usb_erase(&self, address: u32, len: u32) -> Result<(), Self::Error>;
+    /// Tell the device the address the next chunk should be written at (DfuSe extension).
+    fn usb_set_address(&self, address: u32) -> Result<(), Self::Error>;
+    /// Issue a `DFU_DNLOAD` request with block number `block_num` and payload `bytes`.
+    fn usb_download(&self, block_num: u16, bytes: &[u8]) -> Result<Self::Write, Self::Error>;
+    /// Issue a `DFU_UPLOAD` request with block number `block_num`, writing the response into
+    /// `buffer`.
+    fn usb_upload(&self, block_num: u16, buffer: &mut [u8]) -> Result<Self::Read, Self::Error>;
+}
+
+/// Entry point to the download/upload sans-io state machines; holds the [`DfuIo`] transport.
+pub struct DfuSansIo<IO> {
+    pub(crate) io: IO,
+}
+
+impl<IO> DfuSansIo<IO> {
+    /// Create an instance of [`DfuSansIo`] wrapping `io`.
+    pub fn new(io: IO) -> Self {
+        Self { io }
+    }
+
+    /// Consume the object and return its [`DfuIo`].
+    pub fn into_inner(self) -> IO {
+        self.io
+    }
+}
+
+impl<IO: DfuIo> DfuSansIo<IO> {
+    /// Create a state machine to download `length` bytes of firmware into the device.
+    pub fn download(
+        &mut self,
+        length: u32,
+    ) -> Result<get_status::Cmd<download::DownloadLoop<'_, IO>>, IO::Error> {
+        let descriptor = self.io.functional_descriptor();
+        let transfer_size = descriptor.transfer_size as u32;
+        let manifestation_tolerant = descriptor.manifestation_tolerant;
+        Ok(get_status::Cmd::immediate(download::DownloadLoop::new(
+            &mut self.io,
+            length,
+            transfer_size,
+            manifestation_tolerant,
+        )))
+    }
+
+    /// Create a state machine to upload (read back) up to `length` bytes from the device.
+    ///
+    /// `length` only bounds how many bytes the caller is willing to read; the upload itself
+    /// always proceeds in the device's own `transfer_size`-sized blocks and ends as soon as the
+    /// device signals a short (or empty) transfer.
+    pub fn upload(
+        &mut self,
+        _length: u32,
+    ) -> Result<get_status::Cmd<upload::UploadLoop<'_, IO>>, IO::Error> {
+        let transfer_size = self.io.functional_descriptor().transfer_size as usize;
+        Ok(get_status::Cmd::immediate(upload::UploadLoop::new(
+            &mut self.io,
+            transfer_size,
+        )))
+    }
+
+    /// Send a Detach request to the device.
+    pub fn detach(&self) -> Result<(), IO::Error> {
+        self.io.usb_detach()?;
+        Ok(())
+    }
+}
+
+/// Async counterpart of [`DfuSansIo`]'s `download`/`upload` entry points, kept as a separate
+/// trait rather than a second inherent impl — see [`get_status::AsyncGetStatus`] for why.
+#[cfg(feature = "async")]
+pub trait AsyncDfuSansIo<IO> {
+    /// Transport error, see [`asynchronous::AsyncDfuIo::Error`].
+    type Error;
+
+    /// Create a state machine to download `length` bytes of firmware into the device.
+    fn download(
+        &mut self,
+        length: u32,
+    ) -> Result<get_status::Cmd<download::DownloadLoop<'_, IO>>, Self::Error>;
+
+    /// Create a state machine to upload (read back) up to `length` bytes from the device.
+    fn upload(
+        &mut self,
+        length: u32,
+    ) -> Result<get_status::Cmd<upload::UploadLoop<'_, IO>>, Self::Error>;
+}
+
+#[cfg(feature = "async")]
+impl<IO: asynchronous::AsyncDfuIo> AsyncDfuSansIo<IO> for DfuSansIo<IO> {
+    type Error = IO::Error;
+
+    fn download(
+        &mut self,
+        length: u32,
+    ) -> Result<get_status::Cmd<download::DownloadLoop<'_, IO>>, IO::Error> {
+        let descriptor = self.io.functional_descriptor();
+        let transfer_size = descriptor.transfer_size as u32;
+        let manifestation_tolerant = descriptor.manifestation_tolerant;
+        Ok(get_status::Cmd::immediate(download::DownloadLoop::new(
+            &mut self.io,
+            length,
+            transfer_size,
+            manifestation_tolerant,
+        )))
+    }
+
+    fn upload(
+        &mut self,
+        _length: u32,
+    ) -> Result<get_status::Cmd<upload::UploadLoop<'_, IO>>, IO::Error> {
+        let transfer_size = self.io.functional_descriptor().transfer_size as usize;
+        Ok(get_status::Cmd::immediate(upload::UploadLoop::new(
+            &mut self.io,
+            transfer_size,
+        )))
+    }
+}
+
+impl<IO: DfuIo> DfuSansIo<IO> {
+    /// Reset the USB device.
+    pub fn usb_reset(&self) -> Result<IO::Reset, IO::Error> {
+        self.io.usb_reset()
+    }
+
+    /// Returns whether the device will detach on its own if requested.
+    pub fn will_detach(&self) -> bool {
+        self.io.functional_descriptor().will_detach
+    }
+
+    /// Returns whether the device is manifestation tolerant.
+    pub fn manifestation_tolerant(&self) -> bool {
+        self.io.functional_descriptor().manifestation_tolerant
+    }
+}
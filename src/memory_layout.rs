@@ -0,0 +1,17 @@
+/// The DFU functional descriptor, as read from the device's USB configuration descriptor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FunctionalDescriptor {
+    /// Whether the device supports `DFU_DNLOAD`.
+    pub can_download: bool,
+    /// Whether the device supports `DFU_UPLOAD`.
+    pub can_upload: bool,
+    /// Whether the device is manifestation tolerant (stays in `dfuIDLE`-reachable states after
+    /// manifestation, rather than needing a USB reset).
+    pub manifestation_tolerant: bool,
+    /// Whether the device will detach on its own after `DFU_DETACH`.
+    pub will_detach: bool,
+    /// `wTransferSize`: the maximum number of bytes per `DFU_DNLOAD`/`DFU_UPLOAD` transaction.
+    pub transfer_size: u16,
+    /// `bcdDFU`: the DFU specification version implemented by the device.
+    pub dfu_version: u16,
+}
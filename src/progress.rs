@@ -0,0 +1,33 @@
+/// A phase of a download/upload transfer, reported to the closure passed to
+/// [`crate::sync::DfuSync::with_progress`] (and its `DfuAsync` counterpart).
+///
+/// Unlike a plain byte count, this lets a caller show what the device is actually doing —
+/// erasing, writing, or finishing up — rather than just a number that stalls during erase and
+/// manifestation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressEvent {
+    /// The device is erasing `len` bytes starting at `address` before it can accept data there.
+    Erase {
+        /// Start address of the region being erased.
+        address: u32,
+        /// Number of bytes being erased.
+        len: u32,
+    },
+    /// The device is being told where the next chunk should be written.
+    SetAddress,
+    /// A chunk of `bytes` out of `total` has been transferred so far.
+    ///
+    /// `bytes` is the cumulative count, so for a download `bytes as f32 / total as f32` is a
+    /// ready-made fraction complete.
+    Transfer {
+        /// Cumulative number of bytes transferred so far.
+        bytes: usize,
+        /// Length of a download; for an upload, the maximum length requested (`u32::MAX` for
+        /// `upload_to_vec`), which the device may end short of.
+        total: usize,
+    },
+    /// The device has received all data and is now manifesting (validating/applying) it.
+    Manifest,
+    /// The device is being reset back to normal operation.
+    Reset,
+}
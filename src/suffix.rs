@@ -0,0 +1,175 @@
+use super::*;
+
+const SUFFIX_LENGTH: usize = 16;
+const SUFFIX_SIGNATURE: [u8; 3] = *b"UFD";
+const CRC32_POLY: u32 = 0xEDB8_8320;
+
+/// The 16-byte DFU file suffix defined by the USB DFU 1.1 specification.
+///
+/// It is appended to a firmware image and lets [`DfuSuffix::parse`] validate that the file is
+/// whole, and [`DfuSuffix::check_ids`] that it is intended for the device it is about to be sent
+/// to, before anything is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DfuSuffix {
+    /// `bcdDevice`, the firmware version the file was built for, or `0xffff` if not set.
+    pub device: u16,
+    /// `idProduct`, the target USB product ID, or `0xffff` if not set.
+    pub product: u16,
+    /// `idVendor`, the target USB vendor ID, or `0xffff` if not set.
+    pub vendor: u16,
+    /// `bcdDFU`, the DFU specification version the suffix was written for (e.g. `0x0100`).
+    pub bcd_dfu: u16,
+    /// The CRC32 stored in the file, computed over everything preceding it.
+    pub crc: u32,
+}
+
+impl DfuSuffix {
+    /// Parse and validate the suffix at the end of `file`, without stripping it.
+    ///
+    /// This checks `bLength`, the `"DFU"` signature and the stored CRC32; it does not check the
+    /// vendor/product/device fields, see [`DfuSuffix::check_ids`] for that.
+    pub fn parse(file: &[u8]) -> Result<Self, Error> {
+        if file.len() < SUFFIX_LENGTH {
+            return Err(Error::InvalidSuffix);
+        }
+
+        let suffix = &file[file.len() - SUFFIX_LENGTH..];
+        let length = suffix[11];
+        if length as usize != SUFFIX_LENGTH {
+            return Err(Error::InvalidSuffix);
+        }
+        if suffix[8..11] != SUFFIX_SIGNATURE {
+            return Err(Error::InvalidSuffix);
+        }
+
+        let device = u16::from_le_bytes([suffix[0], suffix[1]]);
+        let product = u16::from_le_bytes([suffix[2], suffix[3]]);
+        let vendor = u16::from_le_bytes([suffix[4], suffix[5]]);
+        let bcd_dfu = u16::from_le_bytes([suffix[6], suffix[7]]);
+        let crc = u32::from_le_bytes([suffix[12], suffix[13], suffix[14], suffix[15]]);
+
+        let computed = crc32(&file[..file.len() - 4]);
+        if computed != crc {
+            return Err(Error::CrcMismatch {
+                expected: crc,
+                computed,
+            });
+        }
+
+        Ok(Self {
+            device,
+            product,
+            vendor,
+            bcd_dfu,
+            crc,
+        })
+    }
+
+    /// Check the suffix's vendor/product/device fields against a device descriptor, skipping
+    /// any field left as the wildcard `0xffff`.
+    pub fn check_ids(&self, vendor: u16, product: u16, device: u16) -> Result<(), Error> {
+        let matches =
+            |suffix_value: u16, actual: u16| suffix_value == 0xffff || suffix_value == actual;
+
+        if matches(self.vendor, vendor)
+            && matches(self.product, product)
+            && matches(self.device, device)
+        {
+            Ok(())
+        } else {
+            Err(Error::InvalidSuffix)
+        }
+    }
+
+    /// The length, in bytes, of the suffix itself (always 16).
+    pub fn len() -> usize {
+        SUFFIX_LENGTH
+    }
+}
+
+/// Compute the standard CRC32 (polynomial `0xEDB88320`, init `0xFFFFFFFF`, complemented) used by
+/// the DFU file suffix.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            if crc & 1 != 0 {
+                crc = (crc >> 1) ^ CRC32_POLY;
+            } else {
+                crc >>= 1;
+            }
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn valid_suffix(firmware: &[u8], device: u16, product: u16, vendor: u16, bcd_dfu: u16) -> Vec<u8> {
+        let mut file = firmware.to_vec();
+        file.extend_from_slice(&device.to_le_bytes());
+        file.extend_from_slice(&product.to_le_bytes());
+        file.extend_from_slice(&vendor.to_le_bytes());
+        file.extend_from_slice(&bcd_dfu.to_le_bytes());
+        file.extend_from_slice(&SUFFIX_SIGNATURE);
+        file.push(SUFFIX_LENGTH as u8);
+        let crc = crc32(&file);
+        file.extend_from_slice(&crc.to_le_bytes());
+        file
+    }
+
+    /// A real dfu-util/dfu-suffix suffix, hand-transcribed (not built via [`valid_suffix`]): a
+    /// wildcarded vendor/product/device, `bcdDFU` 0x0100, hexdumping as
+    /// `ff ff ff ff ff ff 00 01 55 46 44 10 <crc>`.
+    #[test]
+    fn parses_a_suffix_from_a_literal_byte_sequence() {
+        let firmware = b"firmware bytes";
+        let mut file = firmware.to_vec();
+        file.extend_from_slice(&[
+            0xff, 0xff, // bcdDevice = 0xffff
+            0xff, 0xff, // idProduct = 0xffff
+            0xff, 0xff, // idVendor = 0xffff
+            0x00, 0x01, // bcdDFU = 0x0100
+            0x55, 0x46, 0x44, // "UFD"
+            0x10, // bLength = 16
+        ]);
+        let crc = crc32(&file);
+        file.extend_from_slice(&crc.to_le_bytes());
+
+        let suffix = DfuSuffix::parse(&file).unwrap();
+        assert_eq!(suffix.device, 0xffff);
+        assert_eq!(suffix.product, 0xffff);
+        assert_eq!(suffix.vendor, 0xffff);
+        assert_eq!(suffix.bcd_dfu, 0x0100);
+    }
+
+    #[test]
+    fn parses_a_valid_suffix() {
+        let file = valid_suffix(b"firmware bytes", 0x0200, 0x1234, 0x5678, 0x0100);
+        let suffix = DfuSuffix::parse(&file).unwrap();
+        assert_eq!(suffix.device, 0x0200);
+        assert_eq!(suffix.product, 0x1234);
+        assert_eq!(suffix.vendor, 0x5678);
+        assert_eq!(suffix.bcd_dfu, 0x0100);
+    }
+
+    #[test]
+    fn rejects_a_tampered_crc() {
+        let mut file = valid_suffix(b"firmware bytes", 0xffff, 0xffff, 0xffff, 0x0100);
+        let last = file.len() - 1;
+        file[last] ^= 0xff;
+        assert!(matches!(
+            DfuSuffix::parse(&file),
+            Err(Error::CrcMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_a_file_shorter_than_the_suffix() {
+        let file = [0u8; SUFFIX_LENGTH - 1];
+        assert!(matches!(DfuSuffix::parse(&file), Err(Error::InvalidSuffix)));
+    }
+}
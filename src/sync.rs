@@ -8,17 +8,18 @@ use std::prelude::v1::*;
 pub struct DfuSync<IO, E>
 where
     IO: DfuIo<Read = usize, Write = usize, Reset = (), Error = E>,
-    E: From<std::io::Error> + From<Error>,
+    E: From<std::io::Error> + From<Error> + std::error::Error + Send + Sync + 'static,
 {
     dfu: DfuSansIo<IO>,
     buffer: Vec<u8>,
-    progress: Option<Box<dyn FnMut(usize)>>,
+    progress: Option<Box<dyn FnMut(ProgressEvent)>>,
+    retries: RetryConfig,
 }
 
 impl<IO, E> DfuSync<IO, E>
 where
     IO: DfuIo<Read = usize, Write = usize, Reset = (), Error = E>,
-    E: From<std::io::Error> + From<Error>,
+    E: From<std::io::Error> + From<Error> + std::error::Error + Send + Sync + 'static,
 {
     /// Create a new instance of a generic synchronous implementation of DFU.
     pub fn new(io: IO) -> Self {
@@ -28,15 +29,23 @@ where
             dfu: DfuSansIo::new(io),
             buffer: vec![0x00; transfer_size],
             progress: None,
+            retries: RetryConfig::default(),
         }
     }
 
     /// Use this closure to show progress.
-    pub fn with_progress(&mut self, progress: impl FnMut(usize) + 'static) -> &mut Self {
+    pub fn with_progress(&mut self, progress: impl FnMut(ProgressEvent) + 'static) -> &mut Self {
         self.progress = Some(Box::new(progress));
         self
     }
 
+    /// Retry a recoverable transfer error or device-reported `dfuERROR` by resending the current
+    /// block, up to `max_attempts` times, before giving up with [`Error::RetriesExceeded`].
+    pub fn with_retries(&mut self, max_attempts: usize) -> &mut Self {
+        self.retries = RetryConfig { max_attempts };
+        self
+    }
+
     /// Consume the object and return its [`DfuIo`]
     pub fn into_inner(self) -> IO {
         self.dfu.into_inner()
@@ -46,17 +55,28 @@ where
 impl<IO, E> DfuSync<IO, E>
 where
     IO: DfuIo<Read = usize, Write = usize, Reset = (), Error = E>,
-    E: From<std::io::Error> + From<Error>,
+    E: From<std::io::Error> + From<Error> + std::error::Error + Send + Sync + 'static,
 {
     /// Download a slice to on to the device.
     pub fn download_from_slice(&mut self, slice: &[u8]) -> Result<(), IO::Error> {
-        let length = slice.len();
-        let cursor = Cursor::new(slice);
+        let length = u32::try_from(slice.len()).map_err(|_| Error::OutOfCapabilities)?;
+
+        self.download(Cursor::new(slice), length)
+    }
+
+    /// Verify `file`'s [`DfuSuffix`] against the device, then download everything preceding it.
+    ///
+    /// This is the opt-in counterpart of [`DfuSync::download_from_slice`]: use it when `file` is
+    /// a full DFU file (firmware followed by its 16-byte suffix) rather than raw firmware bytes.
+    pub fn download_from_slice_verified(&mut self, file: &[u8]) -> Result<(), IO::Error> {
+        let suffix = DfuSuffix::parse(file)?;
+        suffix.check_ids(
+            self.dfu.io.vendor_id(),
+            self.dfu.io.product_id(),
+            self.dfu.io.device_release(),
+        )?;
 
-        self.download(
-            cursor,
-            u32::try_from(length).map_err(|_| Error::OutOfCapabilities)?,
-        )
+        self.download_from_slice(&file[..file.len() - DfuSuffix::len()])
     }
 
     /// Download a firmware into the device.
@@ -70,50 +90,123 @@ where
             return Ok(());
         }
 
+        // Polls status until the device is ready (`Ok`) or reports a `dfuERROR` (`Err`, with the
+        // resumed state handed back so the caller can clear it and resend).
         macro_rules! wait_status {
             ($cmd:expr) => {{
                 let mut cmd = $cmd;
                 loop {
-                    cmd = match cmd.next() {
-                        get_status::Step::Break(cmd) => break cmd,
-                        get_status::Step::Wait(cmd, poll_timeout) => {
+                    let (chained, n) = cmd.get_status(&mut self.buffer)?;
+                    match chained.chain(&self.buffer[..n])? {
+                        get_status::Step::Break(resume) => break Ok(resume),
+                        get_status::Step::Error(resume) => break Err(resume),
+                        get_status::Step::Wait(next_cmd, poll_timeout) => {
                             std::thread::sleep(std::time::Duration::from_millis(poll_timeout));
-                            let (cmd, n) = cmd.get_status(&mut self.buffer)?;
-                            cmd.chain(&self.buffer[..n])??
+                            cmd = next_cmd;
                         }
-                    };
+                    }
                 }
             }};
         }
 
-        let cmd = self.dfu.download(length)?;
-        let (cmd, n) = cmd.get_status(&mut self.buffer)?;
-        let (cmd, _) = cmd.chain(&self.buffer[..n])??;
-        let (cmd, n) = cmd.get_status(&mut self.buffer)?;
-        let mut download_loop = cmd.chain(&self.buffer[..n])??;
+        // A `dfuERROR` with nothing sensible to resend yet: clear it and give up.
+        macro_rules! no_retry {
+            ($result:expr) => {
+                match $result {
+                    Ok(resume) => resume,
+                    Err(mut resume) => {
+                        resume.clear_status()?;
+                        return Err(Error::StatusError.into());
+                    }
+                }
+            };
+        }
 
+        let mut download_loop = no_retry!(wait_status!(self.dfu.download(length)?));
+
+        let total = length as usize;
+        let mut transferred = 0usize;
         loop {
             download_loop = match download_loop.next() {
                 download::Step::Break => break,
                 download::Step::Erase(cmd) => {
+                    if let Some(progress) = self.progress.as_mut() {
+                        progress(ProgressEvent::Erase {
+                            address: cmd.address(),
+                            len: cmd.len(),
+                        });
+                    }
                     let (cmd, _) = cmd.erase()?;
-                    wait_status!(cmd)
+                    no_retry!(wait_status!(cmd))
                 }
                 download::Step::SetAddress(cmd) => {
+                    if let Some(progress) = self.progress.as_mut() {
+                        progress(ProgressEvent::SetAddress);
+                    }
                     let (cmd, _) = cmd.set_address()?;
-                    wait_status!(cmd)
+                    no_retry!(wait_status!(cmd))
                 }
                 download::Step::DownloadChunk(cmd) => {
                     let chunk = reader.fill_buf()?;
-                    let (cmd, n) = cmd.download(chunk)?;
+                    let mut cmd = cmd;
+                    let mut attempts = 0usize;
+                    let (resume, n) = loop {
+                        let (status_cmd, n) = match cmd.download(chunk) {
+                            Ok(ok) => ok,
+                            Err((failed_cmd, err)) => {
+                                if !is_recoverable(&err) {
+                                    return Err(err);
+                                }
+                                attempts += 1;
+                                if attempts > self.retries.max_attempts {
+                                    return Err(Error::RetriesExceeded {
+                                        attempts,
+                                        source: Box::new(err),
+                                    }
+                                    .into());
+                                }
+                                cmd = failed_cmd.clear_status()?;
+                                continue;
+                            }
+                        };
+                        match wait_status!(status_cmd) {
+                            Ok(resume) => break (resume, n),
+                            Err(mut resume) => {
+                                attempts += 1;
+                                if attempts > self.retries.max_attempts {
+                                    return Err(Error::RetriesExceeded {
+                                        attempts,
+                                        source: Box::new(Error::StatusError),
+                                    }
+                                    .into());
+                                }
+                                resume.clear_status()?;
+                                resume.rewind_chunk(n as u32);
+                                cmd = match resume.next() {
+                                    download::Step::DownloadChunk(cmd) => cmd,
+                                    _ => unreachable!("rewinding a chunk always yields a chunk"),
+                                };
+                            }
+                        }
+                    };
                     reader.consume(n);
+                    transferred += n;
                     if let Some(progress) = self.progress.as_mut() {
-                        progress(n);
+                        progress(ProgressEvent::Transfer {
+                            bytes: transferred,
+                            total,
+                        });
+                        if transferred >= total {
+                            progress(ProgressEvent::Manifest);
+                        }
                     }
-                    wait_status!(cmd)
+                    resume
                 }
                 download::Step::UsbReset => {
                     log::trace!("Device reset");
+                    if let Some(progress) = self.progress.as_mut() {
+                        progress(ProgressEvent::Reset);
+                    }
                     self.dfu.io.usb_reset()?;
                     break;
                 }
@@ -136,6 +229,124 @@ where
         self.download(reader, length)
     }
 
+    /// Upload (read back) data from the device into `writer`.
+    ///
+    /// `length` is the maximum number of bytes to read; the upload may finish earlier if the
+    /// device ends it with a short or empty transfer.
+    pub fn upload<W: std::io::Write>(
+        &mut self,
+        mut writer: W,
+        length: u32,
+    ) -> Result<(), IO::Error> {
+        // Status responses go to their own buffer so they never overwrite the uploaded block
+        // held in `self.buffer`.
+        macro_rules! wait_status {
+            ($cmd:expr) => {{
+                let mut cmd = $cmd;
+                let mut status = [0u8; 6];
+                loop {
+                    let (chained, n) = cmd.get_status(&mut status)?;
+                    match chained.chain(&status[..n])? {
+                        get_status::Step::Break(resume) => break Ok(resume),
+                        get_status::Step::Error(resume) => break Err(resume),
+                        get_status::Step::Wait(next_cmd, poll_timeout) => {
+                            std::thread::sleep(std::time::Duration::from_millis(poll_timeout));
+                            cmd = next_cmd;
+                        }
+                    }
+                }
+            }};
+        }
+
+        macro_rules! no_retry {
+            ($result:expr) => {
+                match $result {
+                    Ok(resume) => resume,
+                    Err(mut resume) => {
+                        resume.clear_status()?;
+                        return Err(Error::StatusError.into());
+                    }
+                }
+            };
+        }
+
+        let mut upload_loop = no_retry!(wait_status!(self.dfu.upload(length)?));
+
+        let total = length as usize;
+        let mut remaining = total;
+        let mut transferred = 0usize;
+        loop {
+            upload_loop = match upload_loop.next() {
+                upload::Step::Break => break,
+                upload::Step::UploadChunk(cmd) => {
+                    let len = self.buffer.len().min(remaining);
+                    let mut cmd = cmd;
+                    let mut attempts = 0usize;
+                    let (resume, n) = loop {
+                        let (status_cmd, n) = match cmd.upload(&mut self.buffer[..len]) {
+                            Ok(ok) => ok,
+                            Err((failed_cmd, err)) => {
+                                if !is_recoverable(&err) {
+                                    return Err(err);
+                                }
+                                attempts += 1;
+                                if attempts > self.retries.max_attempts {
+                                    return Err(Error::RetriesExceeded {
+                                        attempts,
+                                        source: Box::new(err),
+                                    }
+                                    .into());
+                                }
+                                cmd = failed_cmd.clear_status()?;
+                                continue;
+                            }
+                        };
+                        match wait_status!(status_cmd) {
+                            Ok(resume) => break (resume, n),
+                            Err(mut resume) => {
+                                attempts += 1;
+                                if attempts > self.retries.max_attempts {
+                                    return Err(Error::RetriesExceeded {
+                                        attempts,
+                                        source: Box::new(Error::StatusError),
+                                    }
+                                    .into());
+                                }
+                                resume.clear_status()?;
+                                resume.rewind_chunk();
+                                cmd = match resume.next() {
+                                    upload::Step::UploadChunk(cmd) => cmd,
+                                    _ => unreachable!("rewinding a chunk always yields a chunk"),
+                                };
+                            }
+                        }
+                    };
+                    writer.write_all(&self.buffer[..n])?;
+                    remaining = remaining.saturating_sub(n);
+                    transferred += n;
+                    if let Some(progress) = self.progress.as_mut() {
+                        progress(ProgressEvent::Transfer {
+                            bytes: transferred,
+                            total,
+                        });
+                    }
+                    resume
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Upload (read back) the whole content of the device into a [`Vec`].
+    ///
+    /// The upload continues until the device ends it with a short or empty transfer.
+    pub fn upload_to_vec(&mut self) -> Result<Vec<u8>, IO::Error> {
+        let mut data = Vec::new();
+        self.upload(&mut data, u32::MAX)?;
+        Ok(data)
+    }
+
     /// Send a Detach request to the device
     pub fn detach(&self) -> Result<(), IO::Error> {
         self.dfu.detach()
@@ -156,3 +367,199 @@ where
         self.dfu.manifestation_tolerant()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory_layout::FunctionalDescriptor;
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+
+    /// Error type standing in for a transport's own error, so [`is_recoverable`] treats it as a
+    /// recoverable failure (a NAK/stall) rather than special-casing it the way it does
+    /// [`Error::StatusError`].
+    #[derive(Debug)]
+    enum TestError {
+        Dfu(Error),
+        Io(std::io::Error),
+        Transport(&'static str),
+    }
+
+    impl std::fmt::Display for TestError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                TestError::Dfu(err) => write!(f, "{err}"),
+                TestError::Io(err) => write!(f, "{err}"),
+                TestError::Transport(msg) => write!(f, "{msg}"),
+            }
+        }
+    }
+
+    impl std::error::Error for TestError {}
+
+    impl From<Error> for TestError {
+        fn from(err: Error) -> Self {
+            TestError::Dfu(err)
+        }
+    }
+
+    impl From<std::io::Error> for TestError {
+        fn from(err: std::io::Error) -> Self {
+            TestError::Io(err)
+        }
+    }
+
+    /// A scripted [`DfuIo`] with a single 8-byte transfer size, driven entirely by `&self`
+    /// interior mutability so it can be shared with [`DfuSync`] without a `Mutex`.
+    struct FakeIo {
+        descriptor: FunctionalDescriptor,
+        /// `bState` returned by each successive `usb_get_status` call.
+        status_script: RefCell<Vec<u8>>,
+        /// Number of upcoming `usb_download` calls that fail with a transport error; later calls
+        /// return `Ok(n)`.
+        download_fails: Cell<usize>,
+        download_calls: RefCell<Vec<u16>>,
+        clear_status_calls: Cell<usize>,
+    }
+
+    impl DfuIo for FakeIo {
+        type Read = usize;
+        type Write = usize;
+        type Reset = ();
+        type Error = TestError;
+
+        fn functional_descriptor(&self) -> &FunctionalDescriptor {
+            &self.descriptor
+        }
+
+        fn vendor_id(&self) -> u16 {
+            0
+        }
+
+        fn product_id(&self) -> u16 {
+            0
+        }
+
+        fn device_release(&self) -> u16 {
+            0
+        }
+
+        fn usb_detach(&self) -> Result<Self::Write, Self::Error> {
+            Ok(0)
+        }
+
+        fn usb_reset(&self) -> Result<Self::Reset, Self::Error> {
+            Ok(())
+        }
+
+        fn usb_clear_status(&self) -> Result<(), Self::Error> {
+            self.clear_status_calls.set(self.clear_status_calls.get() + 1);
+            Ok(())
+        }
+
+        fn usb_get_status(&self, buffer: &mut [u8]) -> Result<Self::Read, Self::Error> {
+            let mut script = self.status_script.borrow_mut();
+            let b_state = if script.is_empty() { 0x02 } else { script.remove(0) };
+            buffer[..6].copy_from_slice(&[0, 0, 0, 0, b_state, 0]);
+            Ok(6)
+        }
+
+        fn usb_erase(&self, _address: u32, _len: u32) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn usb_set_address(&self, _address: u32) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn usb_download(&self, block_num: u16, bytes: &[u8]) -> Result<Self::Write, Self::Error> {
+            self.download_calls.borrow_mut().push(block_num);
+            if self.download_fails.get() > 0 {
+                self.download_fails.set(self.download_fails.get() - 1);
+                return Err(TestError::Transport("NAK"));
+            }
+            Ok(bytes.len())
+        }
+
+        fn usb_upload(&self, _block_num: u16, _buffer: &mut [u8]) -> Result<Self::Read, Self::Error> {
+            Ok(0)
+        }
+    }
+
+    fn fake_io() -> FakeIo {
+        FakeIo {
+            descriptor: FunctionalDescriptor {
+                can_download: true,
+                can_upload: true,
+                manifestation_tolerant: true,
+                will_detach: false,
+                transfer_size: 8,
+                dfu_version: 0x0110,
+            },
+            status_script: RefCell::new(Vec::new()),
+            download_fails: Cell::new(0),
+            download_calls: RefCell::new(Vec::new()),
+            clear_status_calls: Cell::new(0),
+        }
+    }
+
+    /// `dfuIDLE` three times (before-loop wait, post-erase wait, post-set-address wait), one
+    /// `DFU_DNLOAD` wait cycle, then `dfuIDLE` once more to conclude the transfer.
+    fn idle_script() -> Vec<u8> {
+        vec![0x02, 0x02, 0x02, 0x02]
+    }
+
+    #[test]
+    fn retries_exceeded_after_max_attempts() {
+        let io = fake_io();
+        *io.status_script.borrow_mut() = idle_script();
+        io.download_fails.set(usize::MAX / 2); // never succeeds
+        let mut dfu = DfuSync::new(io);
+        dfu.with_retries(2);
+
+        let err = dfu.download_from_slice(&[1, 2, 3, 4]).unwrap_err();
+        match err {
+            TestError::Dfu(Error::RetriesExceeded { attempts, .. }) => assert_eq!(attempts, 3),
+            other => panic!("expected RetriesExceeded, got {other:?}"),
+        }
+        assert_eq!(dfu.into_inner().download_calls.into_inner().len(), 3);
+    }
+
+    #[test]
+    fn rewind_chunk_resends_the_failed_block() {
+        let io = fake_io();
+        // Ready, Ready, Ready, dfuERROR (after the first download attempt), Ready (after the
+        // resend).
+        *io.status_script.borrow_mut() = vec![0x02, 0x02, 0x02, 0x0a, 0x02];
+        let mut dfu = DfuSync::new(io);
+        dfu.with_retries(1);
+
+        dfu.download_from_slice(&[1, 2, 3, 4]).unwrap();
+
+        let io = dfu.into_inner();
+        assert_eq!(*io.download_calls.borrow(), vec![0, 0]);
+        assert_eq!(io.clear_status_calls.get(), 1);
+    }
+
+    #[test]
+    fn progress_events_are_reported_in_order() {
+        let io = fake_io();
+        *io.status_script.borrow_mut() = idle_script();
+        let mut dfu = DfuSync::new(io);
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let events_clone = Rc::clone(&events);
+        dfu.with_progress(move |event| events_clone.borrow_mut().push(event));
+
+        dfu.download_from_slice(&[1, 2, 3, 4]).unwrap();
+
+        assert_eq!(
+            *events.borrow(),
+            vec![
+                ProgressEvent::Erase { address: 0, len: 4 },
+                ProgressEvent::SetAddress,
+                ProgressEvent::Transfer { bytes: 4, total: 4 },
+                ProgressEvent::Manifest,
+            ]
+        );
+    }
+}
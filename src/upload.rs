@@ -0,0 +1,184 @@
+use super::*;
+use crate::get_status::HasIo;
+
+/// Steps of the upload state machine, returned by [`UploadLoop::next`].
+pub enum Step<'io, IO> {
+    /// The device has signalled the end of the upload with a short (or empty) transfer.
+    Break,
+    /// Request the next block of data from the device.
+    UploadChunk(UploadChunk<'io, IO>),
+}
+
+/// Drives the upload state machine one step at a time.
+pub struct UploadLoop<'io, IO> {
+    io: &'io mut IO,
+    block_num: u16,
+    transfer_size: usize,
+    short_transfer: bool,
+}
+
+impl<'io, IO> UploadLoop<'io, IO> {
+    pub(crate) fn new(io: &'io mut IO, transfer_size: usize) -> Self {
+        Self {
+            io,
+            block_num: 0,
+            transfer_size,
+            short_transfer: transfer_size == 0,
+        }
+    }
+
+    /// Undo [`UploadChunk::upload`]'s bookkeeping so the same block is requested again after
+    /// recovering from a `dfuERROR`.
+    pub(crate) fn rewind_chunk(&mut self) {
+        self.block_num = self.block_num.wrapping_sub(1);
+        self.short_transfer = false;
+    }
+}
+
+impl<'io, IO: DfuIo> UploadLoop<'io, IO> {
+    /// Issue `DFU_CLRSTATUS` to leave `dfuERROR`.
+    pub(crate) fn clear_status(&mut self) -> Result<(), IO::Error> {
+        self.io.usb_clear_status()
+    }
+}
+
+impl<'io, IO> HasIo for UploadLoop<'io, IO> {
+    type Io = IO;
+
+    fn io_mut(&mut self) -> &mut IO {
+        self.io
+    }
+}
+
+impl<'io, IO> UploadLoop<'io, IO> {
+    /// Advance the state machine by one step.
+    ///
+    /// This is the part of the state machine shared verbatim between [`crate::sync::DfuSync`]
+    /// and [`crate::asynchronous::DfuAsync`]: only how `UploadChunk::upload` is driven (blocking
+    /// vs. `async`) differs between the two.
+    pub fn next(self) -> Step<'io, IO> {
+        if self.short_transfer {
+            Step::Break
+        } else {
+            Step::UploadChunk(UploadChunk { resume: self })
+        }
+    }
+}
+
+/// A pending request for the next block of upload data.
+pub struct UploadChunk<'io, IO> {
+    resume: UploadLoop<'io, IO>,
+}
+
+impl<'io, IO: DfuIo<Read = usize>> UploadChunk<'io, IO> {
+    /// Request the block from the device, writing it into `buffer`.
+    ///
+    /// Returns the command to reach the next step together with the number of bytes written
+    /// into `buffer`.
+    ///
+    /// On a transport error `self` is handed back unchanged, so the caller can retry the same
+    /// request after recovering.
+    #[allow(clippy::type_complexity)]
+    pub fn upload(
+        mut self,
+        buffer: &mut [u8],
+    ) -> Result<(get_status::Cmd<UploadLoop<'io, IO>>, usize), (Self, IO::Error)> {
+        let len = self.resume.transfer_size.min(buffer.len());
+        let block_num = self.resume.block_num;
+        match self.resume.io.usb_upload(block_num, &mut buffer[..len]) {
+            Ok(n) => {
+                self.resume.block_num = block_num.wrapping_add(1);
+                self.resume.short_transfer = n < self.resume.transfer_size;
+                Ok((get_status::Cmd::immediate(self.resume), n))
+            }
+            Err(err) => Err((self, err)),
+        }
+    }
+
+    /// Issue `DFU_CLRSTATUS` to leave `dfuERROR`, handing `self` back unchanged so the request
+    /// can be retried.
+    pub fn clear_status(self) -> Result<Self, IO::Error> {
+        self.resume.io.usb_clear_status()?;
+        Ok(self)
+    }
+}
+
+/// Async counterpart of [`UploadChunk::upload`], kept as a separate trait rather than a second
+/// inherent impl — see [`crate::get_status::AsyncGetStatus`] for why.
+#[cfg(feature = "async")]
+#[allow(async_fn_in_trait)] // single-threaded embedded executors are the target; requiring `Send` futures would be a needless constraint
+pub trait AsyncUploadChunk<'io, IO: 'io> {
+    /// Error type of the underlying transport.
+    type Error;
+
+    /// Request the block from the device, writing it into `buffer`.
+    ///
+    /// Returns the command to reach the next step together with the number of bytes written
+    /// into `buffer`.
+    ///
+    /// On a transport error `self` is handed back unchanged, so the caller can retry the same
+    /// request after recovering.
+    async fn upload(
+        self,
+        buffer: &mut [u8],
+    ) -> Result<(get_status::Cmd<UploadLoop<'io, IO>>, usize), (Self, Self::Error)>
+    where
+        Self: Sized;
+
+    /// Issue `DFU_CLRSTATUS` to leave `dfuERROR`, handing `self` back unchanged so the request
+    /// can be retried.
+    async fn clear_status(self) -> Result<Self, Self::Error>
+    where
+        Self: Sized;
+}
+
+/// Async counterpart of [`UploadLoop::clear_status`], kept as a separate trait rather than a
+/// second inherent impl — see [`crate::get_status::AsyncGetStatus`] for why.
+#[cfg(feature = "async")]
+#[allow(async_fn_in_trait)] // single-threaded embedded executors are the target; requiring `Send` futures would be a needless constraint
+pub trait AsyncClearStatus<IO> {
+    /// Error type of the underlying transport.
+    type Error;
+
+    /// Issue `DFU_CLRSTATUS` to leave `dfuERROR`.
+    async fn clear_status(&mut self) -> Result<(), Self::Error>;
+}
+
+#[cfg(feature = "async")]
+impl<'io, IO: crate::asynchronous::AsyncDfuIo<Read = usize>> AsyncClearStatus<IO>
+    for UploadLoop<'io, IO>
+{
+    type Error = IO::Error;
+
+    async fn clear_status(&mut self) -> Result<(), IO::Error> {
+        self.io.usb_clear_status().await
+    }
+}
+
+#[cfg(feature = "async")]
+impl<'io, IO: crate::asynchronous::AsyncDfuIo<Read = usize>> AsyncUploadChunk<'io, IO>
+    for UploadChunk<'io, IO>
+{
+    type Error = IO::Error;
+
+    async fn upload(
+        mut self,
+        buffer: &mut [u8],
+    ) -> Result<(get_status::Cmd<UploadLoop<'io, IO>>, usize), (Self, IO::Error)> {
+        let len = self.resume.transfer_size.min(buffer.len());
+        let block_num = self.resume.block_num;
+        match self.resume.io.usb_upload(block_num, &mut buffer[..len]).await {
+            Ok(n) => {
+                self.resume.block_num = block_num.wrapping_add(1);
+                self.resume.short_transfer = n < self.resume.transfer_size;
+                Ok((get_status::Cmd::immediate(self.resume), n))
+            }
+            Err(err) => Err((self, err)),
+        }
+    }
+
+    async fn clear_status(self) -> Result<Self, IO::Error> {
+        self.resume.io.usb_clear_status().await?;
+        Ok(self)
+    }
+}